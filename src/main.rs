@@ -1,7 +1,8 @@
 use anyhow::{Context, Result, anyhow};
 use chrono::{DateTime, Local};
 use clap::{Parser, Subcommand};
-use regex::Regex;
+use filetime::FileTime;
+use ignore::WalkBuilder;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -35,6 +36,23 @@ enum Cli {
 #[derive(Parser)]
 #[command(about = "A tool to show and operate on recently changed crates")]
 struct Args {
+    /// Compare against a base ref (e.g. `origin/main`) in addition to the working tree,
+    /// so crates touched anywhere on the current branch are detected, not just uncommitted
+    /// changes. Defaults to `origin/main` when that ref exists.
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Run the external cargo command across every affected crate instead of just the
+    /// most recent one, passing `--package <name>` once per crate.
+    #[arg(long)]
+    all: bool,
+
+    /// Skip git entirely and pick the crate with the most recently modified file by
+    /// scanning the working directory (honoring `.gitignore`). Used automatically
+    /// when no `.git` directory is found.
+    #[arg(long)]
+    no_git: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -45,6 +63,8 @@ enum Commands {
     Path,
     /// Show the name of the recently changed crate
     Show,
+    /// List every affected crate, most recently changed first
+    List,
     /// Run a cargo command on the recently changed crate
     #[command(external_subcommand)]
     External(Vec<String>),
@@ -57,9 +77,21 @@ fn main() -> Result<()> {
 
     let Cli::Recent(args) = Cli::parse();
 
-    match args.command {
-        Some(Commands::Path) => {
-            let crate_path = find_recent_crate_path()?;
+    let Some(command) = args.command else {
+        println!("No command specified. Try 'cargo recent path' or 'cargo recent show'");
+        return Ok(());
+    };
+
+    // Resolved once per invocation and threaded through every lookup below, instead
+    // of shelling out to `cargo metadata` again for each candidate file or crate.
+    // Not fatal on its own: a git-less scan of a tarball extraction or vendored
+    // tree may have no Cargo.toml reachable from the current directory at all, in
+    // which case crate/name resolution falls back to bare directory names.
+    let packages = cargo_metadata_packages(&std::env::current_dir()?).unwrap_or_default();
+
+    match command {
+        Commands::Path => {
+            let crate_path = find_recent_crate_path(args.since.as_deref(), args.no_git, &packages)?;
             if crate_path.as_os_str().is_empty() {
                 // Print empty string when no changes are detected
                 println!();
@@ -67,52 +99,78 @@ fn main() -> Result<()> {
                 println!("{}", crate_path.display());
             }
         }
-        Some(Commands::Show) => {
-            let crate_path = find_recent_crate_path()?;
+        Commands::Show => {
+            let crate_path = find_recent_crate_path(args.since.as_deref(), args.no_git, &packages)?;
             if crate_path.as_os_str().is_empty() {
                 // Print empty string when no changes are detected
                 println!();
                 return Ok(());
             }
-            let crate_name = get_crate_name(&crate_path)?;
+            let crate_name = get_crate_name(&crate_path, &packages)?;
             println!("{}", crate_name);
         }
-        Some(Commands::External(args)) => {
-            if args.is_empty() {
+        Commands::List => {
+            let crate_paths =
+                find_recent_crate_paths(args.since.as_deref(), args.no_git, &packages)?;
+            for crate_path in &crate_paths {
+                println!("{}", get_crate_name(crate_path, &packages)?);
+            }
+        }
+        Commands::External(cmd_args) => {
+            if cmd_args.is_empty() {
                 return Err(anyhow!("No cargo command specified"));
             }
 
-            let crate_path = find_recent_crate_path()?;
-            if crate_path.as_os_str().is_empty() {
-                // Print empty string and exit when no changes are detected
-                println!();
-                return Ok(());
-            }
-            let crate_name = get_crate_name(&crate_path)?;
+            let crate_names = if args.all {
+                let crate_paths =
+                    find_recent_crate_paths(args.since.as_deref(), args.no_git, &packages)?;
+                if crate_paths.is_empty() {
+                    // Print empty string and exit when no changes are detected
+                    println!();
+                    return Ok(());
+                }
+                crate_paths
+                    .iter()
+                    .map(|crate_path| get_crate_name(crate_path, &packages))
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                let crate_path =
+                    find_recent_crate_path(args.since.as_deref(), args.no_git, &packages)?;
+                if crate_path.as_os_str().is_empty() {
+                    // Print empty string and exit when no changes are detected
+                    println!();
+                    return Ok(());
+                }
+                vec![get_crate_name(&crate_path, &packages)?]
+            };
 
             // Create the command
             let mut cmd = Command::new("cargo");
 
             // Add all subcommands first
-            for arg in &args {
+            for arg in &cmd_args {
                 cmd.arg(arg);
             }
 
-            // Then add the package flag
-            cmd.arg("--package").arg(&crate_name);
+            // Then add a --package flag per affected crate
+            for crate_name in &crate_names {
+                cmd.arg("--package").arg(crate_name);
+            }
 
             // Print the command being executed
             let mut command_str = "run: cargo".to_string();
 
             // Add all subcommands first
-            for arg in &args {
+            for arg in &cmd_args {
                 command_str.push(' ');
                 command_str.push_str(arg);
             }
 
-            // Then add the package flag
-            command_str.push_str(" --package ");
-            command_str.push_str(&crate_name);
+            // Then add the package flags
+            for crate_name in &crate_names {
+                command_str.push_str(" --package ");
+                command_str.push_str(crate_name);
+            }
 
             println!("{}", command_str);
 
@@ -130,56 +188,71 @@ fn main() -> Result<()> {
                 return Err(anyhow!("Command failed"));
             }
         }
-        None => {
-            println!("No command specified. Try 'cargo recent path' or 'cargo recent show'");
-        }
     }
 
     Ok(())
 }
 
-/// Find the path of the recently changed crate
-fn find_recent_crate_path() -> Result<PathBuf> {
-    debug_log!("Entering find_recent_crate_path");
-
-    // First, try to find the repository root
-    let repo_root =
-        find_repo_root().ok_or_else(|| anyhow!("Could not find git repository root"))?;
-
-    debug_log!("Repository root: {}", repo_root.display());
-
-    // Get git diff to find changed files (uncommitted changes only)
-    let output = Command::new("git")
-        .args(["diff", "--name-only"])
-        .current_dir(&repo_root) // Ensure we run git diff from the repository root
-        .output()
-        .context("Failed to execute git diff command")?;
-
-    if !output.status.success() {
-        debug_log!("Git diff command failed with status: {:?}", output.status);
-        debug_log!(
-            "Git diff stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Err(anyhow!("Git diff command failed"));
-    }
+/// Find the path of the single most recently changed crate, for the `path`/`show`
+/// commands and the default (non-`--all`) external passthrough.
+fn find_recent_crate_path(
+    since: Option<&str>,
+    no_git: bool,
+    packages: &[Package],
+) -> Result<PathBuf> {
+    Ok(find_recent_crate_paths(since, no_git, packages)?
+        .into_iter()
+        .next()
+        .unwrap_or_default())
+}
 
-    let diff_output =
-        String::from_utf8(output.stdout).context("Failed to parse git diff output")?;
+/// Find every crate touched by the changed files (via `git diff`, or a git-less mtime
+/// scan as a fallback), ordered by recency and deduped. `packages` is the workspace's
+/// package set, resolved once by the caller and reused here.
+fn find_recent_crate_paths(
+    since: Option<&str>,
+    no_git: bool,
+    packages: &[Package],
+) -> Result<Vec<PathBuf>> {
+    debug_log!("Entering find_recent_crate_paths");
+
+    let repo_root = if no_git { None } else { find_repo_root() };
+
+    let candidates = match repo_root {
+        Some(repo_root) => {
+            debug_log!("Repository root: {}", repo_root.display());
+
+            let changed_files = gather_changed_files(&repo_root, since)?;
+            debug_log!("Changed files: {:?}", changed_files);
+
+            if changed_files.is_empty() {
+                debug_log!("No changes detected");
+                // Return no crates instead of an error when no changes are detected
+                return Ok(Vec::new());
+            }
 
-    debug_log!("Git diff output: {:?}", diff_output);
+            collect_git_candidates(&repo_root, &changed_files)
+        }
+        None => {
+            if !no_git {
+                debug_log!("No git repository found; falling back to a git-less mtime scan");
+            }
+            scan_candidates_without_git(&std::env::current_dir()?)?
+        }
+    };
 
-    if diff_output.trim().is_empty() {
-        debug_log!("No changes detected");
-        // Return empty path instead of error when no changes are detected
-        return Ok(PathBuf::new());
-    }
+    rank_crate_dirs(candidates, packages)
+}
 
-    // Parse the changed files and find the most recently modified one
-    let mut latest_time = DateTime::<Local>::from(std::time::SystemTime::UNIX_EPOCH);
-    let mut latest_file: Option<PathBuf> = None;
+/// Keep the Rust/Cargo files among the changed paths and pair each with its on-disk
+/// modification time, dropping entries that no longer exist (e.g. deleted files).
+fn collect_git_candidates(
+    repo_root: &Path,
+    changed_files: &[String],
+) -> Vec<(DateTime<Local>, PathBuf)> {
+    let mut candidates = Vec::new();
 
-    for file in diff_output.lines() {
+    for file in changed_files {
         debug_log!("Processing file from git diff: {}", file);
 
         // Check if the file is a Rust file (.rs) or Cargo file (Cargo.toml, Cargo.lock)
@@ -202,38 +275,237 @@ fn find_recent_crate_path() -> Result<PathBuf> {
                 if let Ok(modified) = metadata.modified() {
                     let modified_time: DateTime<Local> = modified.into();
                     debug_log!("File modified time: {}", modified_time);
-                    if modified_time > latest_time {
-                        debug_log!("New latest file: {}", file_path.display());
-                        latest_time = modified_time;
-                        latest_file = Some(file_path);
-                    } else if modified_time == latest_time && latest_file.is_some() {
-                        // Tiebreak by filename (ASC sort)
-                        if let Some(ref current_latest) = latest_file {
-                            if file_path.to_string_lossy() < current_latest.to_string_lossy() {
-                                latest_file = Some(file_path);
-                            }
-                        }
-                    }
+                    candidates.push((modified_time, file_path));
                 }
             }
         }
     }
 
-    let latest_file = match latest_file {
-        Some(file) => file,
-        None => {
-            debug_log!("No valid changed files found");
-            return Err(anyhow!("No valid changed files found"));
+    candidates
+}
+
+/// Walk the directory tree from `start_dir` without relying on git, honoring
+/// `.gitignore` rules so that `target/` and other ignored paths are skipped. Used in
+/// `--no-git` mode and as the automatic fallback when no `.git` directory is found.
+fn scan_candidates_without_git(start_dir: &Path) -> Result<Vec<(DateTime<Local>, PathBuf)>> {
+    debug_log!(
+        "Scanning for Rust/Cargo files without git from: {}",
+        start_dir.display()
+    );
+
+    let mut candidates = Vec::new();
+
+    // `require_git` defaults to true, which disables .gitignore/.git/info/exclude
+    // matching entirely unless an ancestor .git directory is found. This mode exists
+    // specifically for trees without one (tarball extractions, vendored copies), so
+    // ignore rules must still apply.
+    let walker = WalkBuilder::new(start_dir).require_git(false).build();
+
+    for entry in walker {
+        // A single unreadable entry (permission-denied directory, broken symlink) in
+        // a vendored tree shouldn't abort the whole best-effort scan.
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_err) => {
+                debug_log!("Skipping unreadable entry: {}", _err);
+                continue;
+            }
+        };
+
+        let is_rust_file = entry.path().extension().is_some_and(|ext| ext == "rs");
+        let is_cargo_file = entry
+            .path()
+            .file_name()
+            .is_some_and(|name| name == "Cargo.toml" || name == "Cargo.lock");
+
+        if !is_rust_file && !is_cargo_file {
+            continue;
         }
-    };
 
-    debug_log!("Latest file: {}", latest_file.display());
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
 
-    // Find the crate directory containing this file
-    let crate_dir = find_crate_directory(&latest_file)?;
-    debug_log!("Crate directory: {}", crate_dir.display());
+        if !metadata.is_file() {
+            continue;
+        }
 
-    Ok(crate_dir)
+        let modified_time = file_time_to_local(FileTime::from_last_modification_time(&metadata));
+        debug_log!(
+            "Found file: {} (modified: {})",
+            entry.path().display(),
+            modified_time
+        );
+        candidates.push((modified_time, entry.path().to_path_buf()));
+    }
+
+    Ok(candidates)
+}
+
+/// Convert a `filetime::FileTime` to a `chrono::DateTime<Local>` so git-based and
+/// git-less candidates can be ranked the same way.
+fn file_time_to_local(file_time: FileTime) -> DateTime<Local> {
+    let system_time = std::time::UNIX_EPOCH
+        + std::time::Duration::new(
+            file_time.unix_seconds().max(0) as u64,
+            file_time.nanoseconds(),
+        );
+    DateTime::<Local>::from(system_time)
+}
+
+/// Rank candidate files by recency (most recent first, tiebreaking on filename ASC)
+/// and resolve each to its crate directory, keeping only the first (i.e. most recent)
+/// occurrence of each distinct crate.
+fn rank_crate_dirs(
+    mut candidates: Vec<(DateTime<Local>, PathBuf)>,
+    packages: &[Package],
+) -> Result<Vec<PathBuf>> {
+    if candidates.is_empty() {
+        debug_log!("No valid changed files found");
+        return Err(anyhow!("No valid changed files found"));
+    }
+
+    candidates.sort_by(|(time_a, path_a), (time_b, path_b)| {
+        time_b
+            .cmp(time_a)
+            .then_with(|| path_a.to_string_lossy().cmp(&path_b.to_string_lossy()))
+    });
+
+    let mut crate_dirs: Vec<PathBuf> = Vec::new();
+    for (_, file_path) in candidates {
+        let crate_dir = match find_crate_directory(&file_path, packages) {
+            Ok(dir) => dir,
+            Err(_err) => {
+                // One unmappable candidate shouldn't sink the whole command when
+                // other, resolvable candidates exist.
+                debug_log!(
+                    "Skipping unresolvable candidate {}: {}",
+                    file_path.display(),
+                    _err
+                );
+                continue;
+            }
+        };
+        debug_log!(
+            "Crate directory for {}: {}",
+            file_path.display(),
+            crate_dir.display()
+        );
+        if !crate_dirs.contains(&crate_dir) {
+            crate_dirs.push(crate_dir);
+        }
+    }
+
+    Ok(crate_dirs)
+}
+
+/// Collect the set of changed files to consider, as paths relative to `repo_root`: the
+/// union of unstaged, staged, and untracked-but-not-ignored changes, plus anything
+/// changed since `since` diverged from its target (explicit `--since`, or the default
+/// `origin/main` when that ref exists).
+fn gather_changed_files(repo_root: &Path, since: Option<&str>) -> Result<Vec<String>> {
+    let mut files = run_git_lines(repo_root, &["diff", "--name-only"])?;
+    files.extend(run_git_lines(
+        repo_root,
+        &["diff", "--name-only", "--cached"],
+    )?);
+    // Untracked-but-not-ignored files (e.g. a brand-new module or a freshly
+    // `cargo new`'d crate) show up in neither `git diff` nor `git diff --cached`.
+    files.extend(run_git_lines(
+        repo_root,
+        &["ls-files", "--others", "--exclude-standard"],
+    )?);
+
+    let since = since
+        .map(ToOwned::to_owned)
+        .or_else(|| default_since_ref(repo_root));
+
+    if let Some(since) = since {
+        debug_log!("Diffing against base ref: {}", since);
+
+        let merge_base_output = Command::new("git")
+            .args(["merge-base", "HEAD", &since])
+            .current_dir(repo_root)
+            .output()
+            .context("Failed to execute git merge-base command")?;
+
+        if !merge_base_output.status.success() {
+            return Err(anyhow!(
+                "Could not find a merge base between HEAD and '{}': {}",
+                since,
+                String::from_utf8_lossy(&merge_base_output.stderr).trim()
+            ));
+        }
+
+        let merge_base = String::from_utf8(merge_base_output.stdout)
+            .context("Failed to parse git merge-base output")?
+            .trim()
+            .to_string();
+
+        debug_log!("Merge base: {}", merge_base);
+
+        let range = format!("{}..HEAD", merge_base);
+        files.extend(run_git_lines(repo_root, &["diff", "--name-only", &range])?);
+    }
+
+    files.sort();
+    files.dedup();
+
+    Ok(files)
+}
+
+/// Determine the default base ref to diff against when `--since` is not given.
+///
+/// Returns `origin/main` if that ref exists in the repository, otherwise `None`
+/// (in which case only uncommitted changes are inspected: unstaged, staged and untracked).
+fn default_since_ref(repo_root: &Path) -> Option<String> {
+    let candidate = "origin/main";
+
+    let status = Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", candidate])
+        .current_dir(repo_root)
+        .output()
+        .ok()?
+        .status;
+
+    if status.success() {
+        Some(candidate.to_string())
+    } else {
+        None
+    }
+}
+
+/// Run `git <args>` in `repo_root` and return the non-empty lines of its output.
+fn run_git_lines(repo_root: &Path, args: &[&str]) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo_root)
+        .output()
+        .with_context(|| format!("Failed to execute git {}", args.join(" ")))?;
+
+    if !output.status.success() {
+        debug_log!(
+            "git {} failed with status: {:?}",
+            args.join(" "),
+            output.status
+        );
+        debug_log!(
+            "git {} stderr: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(anyhow!("git {} command failed", args.join(" ")));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| "Failed to parse git output".to_string())?;
+
+    Ok(stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect())
 }
 
 /// Find the Git repository root directory
@@ -256,8 +528,69 @@ fn find_repo_root() -> Option<PathBuf> {
     }
 }
 
-/// Find the crate directory containing the given file
-fn find_crate_directory(file_path: &Path) -> Result<PathBuf> {
+/// A workspace member, as reported by `cargo metadata`.
+struct Package {
+    /// Directory containing the package's `Cargo.toml` (its manifest's parent directory).
+    dir: PathBuf,
+    name: String,
+}
+
+/// Ask cargo for the authoritative set of workspace packages, rather than walking up
+/// directories and regex-scraping `Cargo.toml` files. This correctly handles virtual
+/// workspace manifests and packages whose directory name differs from the crate name.
+fn cargo_metadata_packages(start_dir: &Path) -> Result<Vec<Package>> {
+    debug_log!("Running cargo metadata from: {}", start_dir.display());
+
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(start_dir)
+        .output()
+        .context("Failed to execute cargo metadata")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let metadata: serde_json::Value =
+        serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata output")?;
+
+    let packages = metadata
+        .get("packages")
+        .and_then(serde_json::Value::as_array)
+        .ok_or_else(|| anyhow!("Unexpected cargo metadata output: missing 'packages'"))?;
+
+    packages
+        .iter()
+        .map(|package| {
+            let name = package
+                .get("name")
+                .and_then(serde_json::Value::as_str)
+                .ok_or_else(|| anyhow!("Unexpected cargo metadata output: missing package name"))?
+                .to_string();
+
+            let manifest_path = package
+                .get("manifest_path")
+                .and_then(serde_json::Value::as_str)
+                .ok_or_else(|| {
+                    anyhow!("Unexpected cargo metadata output: missing manifest_path")
+                })?;
+
+            let dir = Path::new(manifest_path)
+                .parent()
+                .ok_or_else(|| anyhow!("Unexpected manifest path: {}", manifest_path))?
+                .to_path_buf();
+
+            Ok(Package { dir, name })
+        })
+        .collect()
+}
+
+/// Find the crate directory containing the given file, out of the given (already
+/// resolved) workspace `packages`.
+fn find_crate_directory(file_path: &Path, packages: &[Package]) -> Result<PathBuf> {
     debug_log!("Finding crate directory for file: {}", file_path.display());
 
     // Get the absolute path of the file
@@ -268,114 +601,29 @@ fn find_crate_directory(file_path: &Path) -> Result<PathBuf> {
     };
     debug_log!("Absolute file path: {}", abs_file_path.display());
 
-    // Start from the file's directory and traverse up until we find a Cargo.toml
-    let mut current = abs_file_path.parent().unwrap_or(Path::new("/"));
-    debug_log!("Starting search from directory: {}", current.display());
-
-    // Keep track of the repository root if we find it
-    let mut repo_root: Option<PathBuf> = None;
-
-    // Traverse up until we find a directory with a Cargo.toml file or reach the filesystem root
-    while current != Path::new("") && current != Path::new("/") {
-        debug_log!("Checking directory: {}", current.display());
-
-        // Check if this directory has a Cargo.toml
-        let cargo_toml = current.join("Cargo.toml");
-        if cargo_toml.exists() {
-            debug_log!("Found Cargo.toml at: {}", cargo_toml.display());
-
-            // Check if this is a workspace root
-            let cargo_content =
-                fs::read_to_string(&cargo_toml).context("Failed to read Cargo.toml")?;
-
-            let is_workspace = cargo_content.contains("[workspace]");
-
-            if is_workspace {
-                debug_log!("This is a workspace root");
-                // Remember this as the repository root, but continue searching
-                // for a more specific crate directory
-                repo_root = Some(current.to_path_buf());
-            } else {
-                // This is a regular crate, not a workspace root
-                // Return this directory immediately
-                debug_log!("Found regular crate directory: {}", current.display());
-                return Ok(current.to_path_buf());
-            }
-        }
-
-        // Check if this directory has a .git directory (repository root)
-        if repo_root.is_none() {
-            let git_dir = current.join(".git");
-            if git_dir.exists() && git_dir.is_dir() {
-                debug_log!("Found repository root at: {}", current.display());
-                repo_root = Some(current.to_path_buf());
-            }
-        }
-
-        // Move to the parent directory
-        if let Some(parent) = current.parent() {
-            current = parent;
-        } else {
-            break;
-        }
+    // The containing package is the one whose manifest directory is the longest
+    // path prefix of the file, which correctly picks the most specific member
+    // even when the repo root is a virtual workspace manifest.
+    if let Some(dir) = packages
+        .iter()
+        .filter(|package| abs_file_path.starts_with(&package.dir))
+        .max_by_key(|package| package.dir.as_os_str().len())
+        .map(|package| package.dir.clone())
+    {
+        return Ok(dir);
     }
 
-    // If we found a repository root with a workspace, try to find the specific crate
-    // that contains the file
-    if let Some(root) = repo_root {
-        let root_cargo_toml = root.join("Cargo.toml");
-        if root_cargo_toml.exists() {
-            let cargo_content =
-                fs::read_to_string(&root_cargo_toml).context("Failed to read root Cargo.toml")?;
-
-            if cargo_content.contains("[workspace]") {
-                debug_log!(
-                    "Checking workspace members for file: {}",
-                    abs_file_path.display()
-                );
-
-                // Try to find the most specific crate directory that contains the file
-                // by traversing up from the file's directory
-                let mut current = abs_file_path.parent().unwrap_or(Path::new("/"));
-
-                while current != Path::new("") && current != Path::new("/") && current != root {
-                    let cargo_toml = current.join("Cargo.toml");
-                    if cargo_toml.exists() {
-                        debug_log!("Found subcrate Cargo.toml at: {}", cargo_toml.display());
-                        return Ok(current.to_path_buf());
-                    }
-
-                    if let Some(parent) = current.parent() {
-                        current = parent;
-                    } else {
-                        break;
-                    }
-                }
-
-                // If we couldn't find a specific crate by traversing up,
-                // return the workspace root as a fallback
-                debug_log!(
-                    "No specific crate found, returning workspace root: {}",
-                    root.display()
-                );
-                return Ok(root);
-            }
+    // Not a `cargo metadata` member: fall back to the nearest ancestor directory
+    // with a Cargo.toml. This covers the virtual workspace root itself (editing
+    // its Cargo.toml/Cargo.lock is a normal change, but it has no `[package]`
+    // table of its own) and a brand-new crate that hasn't been added to
+    // `workspace.members` yet.
+    let mut dir = abs_file_path.parent();
+    while let Some(candidate) = dir {
+        if candidate.join("Cargo.toml").is_file() {
+            return Ok(candidate.to_path_buf());
         }
-
-        // If it's not a workspace but has a repository root, return the root
-        return Ok(root);
-    }
-
-    // If we couldn't find any Cargo.toml or repository root,
-    // check if the current directory has a Cargo.toml
-    let current_dir = std::env::current_dir()?;
-    let current_cargo_toml = current_dir.join("Cargo.toml");
-    if current_cargo_toml.exists() {
-        debug_log!(
-            "Using current directory as crate directory: {}",
-            current_dir.display()
-        );
-        return Ok(current_dir);
+        dir = candidate.parent();
     }
 
     Err(anyhow!(
@@ -383,26 +631,25 @@ fn find_crate_directory(file_path: &Path) -> Result<PathBuf> {
     ))
 }
 
-/// Get the crate name from the crate directory
-fn get_crate_name(crate_dir: &Path) -> Result<String> {
-    let cargo_toml = crate_dir.join("Cargo.toml");
-    let content = fs::read_to_string(cargo_toml).context("Failed to read Cargo.toml")?;
-
-    // Extract the package name using regex
-    let re = Regex::new(r#"(?m)^\s*name\s*=\s*"([^"]+)""#).context("Failed to compile regex")?;
-
-    if let Some(captures) = re.captures(&content) {
-        if let Some(name) = captures.get(1) {
-            return Ok(name.as_str().to_string());
-        }
+/// Get the crate name for `crate_dir`, out of the given (already resolved) workspace
+/// `packages`. Falls back to the directory's bare name when `crate_dir` isn't a
+/// `cargo metadata` member (e.g. a virtual workspace root, a brand-new crate not yet
+/// added to `workspace.members`, or `packages` being empty because no manifest was
+/// reachable at all).
+fn get_crate_name(crate_dir: &Path, packages: &[Package]) -> Result<String> {
+    if let Some(package) = packages.iter().find(|package| package.dir == crate_dir) {
+        return Ok(package.name.clone());
     }
 
-    // Fallback: use directory name
-    Ok(crate_dir
+    crate_dir
         .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string())
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            anyhow!(
+                "Could not determine a crate name for directory: {}",
+                crate_dir.display()
+            )
+        })
 }
 
 #[cfg(test)]
@@ -414,12 +661,16 @@ mod tests {
     use tempfile::tempdir;
 
     #[test]
-    fn test_get_crate_name_from_cargo_toml() {
+    fn test_get_crate_name_from_cargo_metadata() {
         // Create a temporary directory
         let temp_dir = tempdir().unwrap();
         let temp_path = temp_dir.path();
 
-        // Create a Cargo.toml file with a test crate name
+        // Create a minimal crate: Cargo.toml plus a source target, since `cargo
+        // metadata` refuses to parse a manifest with no targets.
+        fs::create_dir_all(temp_path.join("src")).unwrap();
+        File::create(temp_path.join("src/main.rs")).unwrap();
+
         let cargo_toml_path = temp_path.join("Cargo.toml");
         let mut cargo_toml = File::create(&cargo_toml_path).unwrap();
         writeln!(
@@ -433,24 +684,37 @@ edition = "2021"
         .unwrap();
 
         // Test get_crate_name function
-        let crate_name = get_crate_name(temp_path).unwrap();
+        let packages = cargo_metadata_packages(temp_path).unwrap();
+        let crate_name = get_crate_name(temp_path, &packages).unwrap();
         assert_eq!(crate_name, "test-crate");
     }
 
     #[test]
-    fn test_get_crate_name_fallback() {
-        // Create a temporary directory with a name but no Cargo.toml
+    fn test_cargo_metadata_packages_invalid_manifest() {
+        // Create a temporary directory with a Cargo.toml that isn't a valid manifest
         let temp_dir = tempdir().unwrap();
-        let temp_path = temp_dir.path().join("fallback-crate");
+        let temp_path = temp_dir.path().join("invalid-crate");
         fs::create_dir(&temp_path).unwrap();
 
-        // Create an empty file that is not a valid Cargo.toml
         let invalid_cargo_toml = temp_path.join("Cargo.toml");
         File::create(&invalid_cargo_toml).unwrap();
 
-        // This should fall back to the directory name
-        let result = get_crate_name(&temp_path);
-        assert!(result.is_err() || result.unwrap() == "fallback-crate");
+        // An unresolvable manifest is a hard error, surfaced as soon as `cargo
+        // metadata` itself is run.
+        let result = cargo_metadata_packages(&temp_path);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_get_crate_name_falls_back_to_bare_directory_name() {
+        // No packages at all (e.g. `cargo metadata` failed or wasn't a member match):
+        // falls back to the directory's own name rather than erroring.
+        let temp_dir = tempdir().unwrap();
+        let crate_dir = temp_dir.path().join("some-crate");
+        fs::create_dir(&crate_dir).unwrap();
+
+        let name = get_crate_name(&crate_dir, &[]).unwrap();
+        assert_eq!(name, "some-crate");
     }
 
     #[test]
@@ -487,7 +751,8 @@ edition = "2021"
         .unwrap();
 
         // Test finding the crate directory from a file
-        let found_dir = find_crate_directory(&file_path).unwrap();
+        let packages = cargo_metadata_packages(&crate_dir).unwrap();
+        let found_dir = find_crate_directory(&file_path, &packages).unwrap();
 
         // Convert paths to canonical form for comparison
         let found_path = found_dir.canonicalize().unwrap();
@@ -505,6 +770,299 @@ edition = "2021"
         env::set_current_dir(original_dir).unwrap();
     }
 
+    #[test]
+    fn test_find_crate_directory_falls_back_to_workspace_root() {
+        // A virtual workspace: the root Cargo.toml has no `[package]` table of its
+        // own, so it's never reported as a `cargo metadata` package.
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("member/src")).unwrap();
+        File::create(root.join("member/src/lib.rs")).unwrap();
+        let mut member_toml = File::create(root.join("member/Cargo.toml")).unwrap();
+        writeln!(
+            member_toml,
+            r#"[package]
+name = "member"
+version = "0.1.0"
+edition = "2021"
+"#
+        )
+        .unwrap();
+        let mut root_toml = File::create(root.join("Cargo.toml")).unwrap();
+        writeln!(
+            root_toml,
+            r#"[workspace]
+members = ["member"]
+"#
+        )
+        .unwrap();
+
+        let packages = cargo_metadata_packages(root).unwrap();
+
+        // Editing the workspace root's own Cargo.toml falls back to the root
+        // directory instead of erroring, since it isn't a member package.
+        let found = find_crate_directory(&root.join("Cargo.toml"), &packages).unwrap();
+        assert_eq!(found, root);
+    }
+
+    #[test]
+    fn test_rank_crate_dirs_skips_unresolvable_candidates() {
+        let temp_dir = tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let crate_a = temp_path.join("crate-a");
+        fs::create_dir_all(&crate_a).unwrap();
+
+        let packages = vec![Package {
+            dir: crate_a.clone(),
+            name: "crate-a".to_string(),
+        }];
+
+        let now = Local::now();
+        let candidates = vec![
+            // Outside of any known crate or Cargo.toml ancestor: unresolvable.
+            (now, PathBuf::from("/no/such/crate/anywhere/file.rs")),
+            (now, crate_a.join("lib.rs")),
+        ];
+
+        // The unresolvable candidate is skipped rather than erroring out the
+        // whole command; the resolvable one still comes through.
+        let crate_dirs = rank_crate_dirs(candidates, &packages).unwrap();
+        assert_eq!(crate_dirs, vec![crate_a]);
+    }
+
+    #[test]
+    fn test_rank_crate_dirs_orders_by_recency_and_dedups() {
+        use chrono::Duration;
+
+        let temp_dir = tempdir().unwrap();
+        let temp_path = temp_dir.path();
+
+        let crate_a = temp_path.join("crate-a");
+        let crate_b = temp_path.join("crate-b");
+        fs::create_dir_all(&crate_a).unwrap();
+        fs::create_dir_all(&crate_b).unwrap();
+
+        let packages = vec![
+            Package {
+                dir: crate_a.clone(),
+                name: "crate-a".to_string(),
+            },
+            Package {
+                dir: crate_b.clone(),
+                name: "crate-b".to_string(),
+            },
+        ];
+
+        let now = Local::now();
+        // Two files in crate-a (oldest and newest) and one in crate-b (middle),
+        // fed out of order to exercise the sort.
+        let candidates = vec![
+            (now - Duration::seconds(10), crate_a.join("old.rs")),
+            (now, crate_a.join("new.rs")),
+            (now - Duration::seconds(5), crate_b.join("lib.rs")),
+        ];
+
+        let crate_dirs = rank_crate_dirs(candidates, &packages).unwrap();
+
+        // crate-a appears once, at the position of its most recent file (new.rs),
+        // ahead of crate-b's older file.
+        assert_eq!(crate_dirs, vec![crate_a, crate_b]);
+    }
+
+    #[test]
+    fn test_rank_crate_dirs_rejects_empty_candidates() {
+        let result = rank_crate_dirs(Vec::new(), &[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_scan_candidates_without_git_honors_gitignore() {
+        // A plain directory tree with no .git anywhere, mimicking a tarball
+        // extraction or vendored checkout.
+        let temp_dir = tempdir().unwrap();
+        let temp_path = temp_dir.path();
+
+        // A real source file that should be picked up.
+        let src_dir = temp_path.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        File::create(src_dir.join("lib.rs")).unwrap();
+
+        // An ignored build-output directory containing a newer, decoy Cargo.toml.
+        // Without .gitignore support this would be picked as the "most recent" file.
+        let target_dir = temp_path.join("target");
+        fs::create_dir_all(&target_dir).unwrap();
+        File::create(target_dir.join("Cargo.toml")).unwrap();
+
+        let mut gitignore = File::create(temp_path.join(".gitignore")).unwrap();
+        writeln!(gitignore, "target/").unwrap();
+
+        let candidates = scan_candidates_without_git(temp_path).unwrap();
+        let found_paths: Vec<_> = candidates.into_iter().map(|(_, path)| path).collect();
+
+        assert!(
+            found_paths.contains(&src_dir.join("lib.rs")),
+            "expected the real source file to be scanned"
+        );
+        assert!(
+            !found_paths.contains(&target_dir.join("Cargo.toml")),
+            "expected the .gitignore'd target/ directory to be skipped: {:?}",
+            found_paths
+        );
+    }
+
+    /// Initialize a throwaway git repository at `path` with a single commit,
+    /// for tests that need real `git diff`/`merge-base` plumbing.
+    fn init_git_repo(path: &Path) {
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(path)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        };
+
+        run(&["init", "--quiet"]);
+        run(&["config", "user.email", "test@example.com"]);
+        run(&["config", "user.name", "Test"]);
+        File::create(path.join("README.md")).unwrap();
+        run(&["add", "."]);
+        run(&["commit", "--quiet", "-m", "initial commit"]);
+    }
+
+    #[test]
+    fn test_gather_changed_files_empty_when_no_changes() {
+        let temp_dir = tempdir().unwrap();
+        let repo_root = temp_dir.path();
+        init_git_repo(repo_root);
+
+        let files = gather_changed_files(repo_root, None).unwrap();
+        assert!(files.is_empty(), "expected no changes: {:?}", files);
+    }
+
+    #[test]
+    fn test_gather_changed_files_includes_staged_and_untracked() {
+        let temp_dir = tempdir().unwrap();
+        let repo_root = temp_dir.path();
+        init_git_repo(repo_root);
+
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(repo_root)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        };
+
+        // A staged-but-not-committed change to an existing file...
+        fs::write(repo_root.join("README.md"), "staged change\n").unwrap();
+        run(&["add", "README.md"]);
+
+        // ...and a brand-new file that's untracked entirely.
+        File::create(repo_root.join("new_module.rs")).unwrap();
+
+        let files = gather_changed_files(repo_root, None).unwrap();
+
+        assert!(
+            files.iter().any(|f| f == "README.md"),
+            "expected the staged change to be included: {:?}",
+            files
+        );
+        assert!(
+            files.iter().any(|f| f == "new_module.rs"),
+            "expected the untracked file to be included: {:?}",
+            files
+        );
+    }
+
+    #[test]
+    fn test_gather_changed_files_invalid_since_errors() {
+        let temp_dir = tempdir().unwrap();
+        let repo_root = temp_dir.path();
+        init_git_repo(repo_root);
+
+        let result = gather_changed_files(repo_root, Some("no-such-ref"));
+        assert!(
+            result.is_err(),
+            "expected an error for a ref with no merge base"
+        );
+    }
+
+    #[test]
+    fn test_find_recent_crate_paths_handles_untracked_crate_not_in_members() {
+        // End-to-end: a freshly `cargo new`'d crate, added to the workspace
+        // directory but not yet to `workspace.members`, whose files show up as
+        // untracked, still resolves to its own directory.
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+
+        fs::create_dir_all(root.join("existing/src")).unwrap();
+        File::create(root.join("existing/src/lib.rs")).unwrap();
+        let mut existing_toml = File::create(root.join("existing/Cargo.toml")).unwrap();
+        writeln!(
+            existing_toml,
+            r#"[package]
+name = "existing"
+version = "0.1.0"
+edition = "2021"
+"#
+        )
+        .unwrap();
+
+        let mut root_toml = File::create(root.join("Cargo.toml")).unwrap();
+        writeln!(
+            root_toml,
+            r#"[workspace]
+members = ["existing"]
+"#
+        )
+        .unwrap();
+
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(root)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        run(&["init", "--quiet"]);
+        run(&["config", "user.email", "test@example.com"]);
+        run(&["config", "user.name", "Test"]);
+        run(&["add", "."]);
+        run(&["commit", "--quiet", "-m", "initial commit"]);
+
+        // A brand-new crate, untracked and not yet listed in `members`.
+        fs::create_dir_all(root.join("new-crate/src")).unwrap();
+        File::create(root.join("new-crate/src/lib.rs")).unwrap();
+        let mut new_toml = File::create(root.join("new-crate/Cargo.toml")).unwrap();
+        writeln!(
+            new_toml,
+            r#"[package]
+name = "new-crate"
+version = "0.1.0"
+edition = "2021"
+"#
+        )
+        .unwrap();
+
+        let original_dir = env::current_dir().unwrap();
+        env::set_current_dir(root).unwrap();
+
+        let packages = cargo_metadata_packages(root).unwrap();
+        let result = find_recent_crate_paths(None, false, &packages);
+
+        env::set_current_dir(original_dir).unwrap();
+
+        let crate_paths = result.unwrap();
+        assert!(
+            crate_paths.contains(&root.join("new-crate")),
+            "expected the untracked, not-yet-a-member crate to resolve: {:?}",
+            crate_paths
+        );
+    }
+
     // This test requires a git repository, so we'll make it conditional
     #[test]
     #[ignore = "Requires a git repository with changes"]
@@ -513,7 +1071,8 @@ edition = "2021"
         let _current_dir = env::current_dir().unwrap();
 
         // This test assumes it's run from a git repository with changes
-        let crate_path = find_recent_crate_path().unwrap();
+        let packages = cargo_metadata_packages(&_current_dir).unwrap();
+        let crate_path = find_recent_crate_path(None, false, &packages).unwrap();
 
         // If there are no changes, this should return an empty path
         if crate_path.as_os_str().is_empty() {